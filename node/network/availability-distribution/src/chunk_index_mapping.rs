@@ -0,0 +1,117 @@
+// Copyright 2023 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! `ValidatorIndex` to `ChunkIndex` mapping used for systematic chunk recovery.
+//!
+//! Systematic recovery reconstructs the available data by concatenating the first
+//! `ceil(n_validators / 3)` systematic erasure chunks, skipping Reed-Solomon decoding
+//! entirely. If we always asked for the same first third of validators we would hammer the
+//! same peers every session, so the chunk a validator is responsible for is rotated per
+//! availability core.
+
+use polkadot_primitives::v1::{CoreIndex, ValidatorIndex};
+
+/// Index of an erasure chunk within the set handed to the erasure-coding crate.
+///
+/// Distinct from [`ValidatorIndex`] once the on-chain `FeatureIndex::AvailabilityChunkMapping`
+/// node feature bit is enabled: which validator is responsible for which chunk is then rotated per core
+/// instead of being the identity mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkIndex(pub u32);
+
+/// Compute the `ChunkIndex` a given validator is responsible for on a particular core.
+///
+/// When `chunk_mapping_enabled` is `false` this is the identity mapping, preserving today's
+/// behaviour (`chunk_index == validator_index`). Once enabled, the mapping is a simple
+/// core-keyed rotation: `chunk_index = (validator_index + core_offset) mod n_validators`,
+/// where `core_offset` is derived from the core index so that different cores don't all ask
+/// the same first-third of validators for their systematic chunks.
+pub fn get_chunk_index(
+	n_validators: usize,
+	validator_index: ValidatorIndex,
+	core_index: CoreIndex,
+	chunk_mapping_enabled: bool,
+) -> ChunkIndex {
+	if !chunk_mapping_enabled || n_validators == 0 {
+		return ChunkIndex(validator_index.0)
+	}
+
+	let core_offset = core_index.0 % n_validators as u32;
+	let chunk_index = (validator_index.0 + core_offset) % n_validators as u32;
+	ChunkIndex(chunk_index)
+}
+
+/// Number of systematic chunks needed to reconstruct the available data without running
+/// Reed-Solomon decoding: the first `ceil(n_validators / 3)` chunks.
+pub fn systematic_chunk_count(n_validators: usize) -> usize {
+	(n_validators + 2) / 3
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mapping_disabled_is_identity() {
+		for validator_index in 0..10 {
+			assert_eq!(
+				get_chunk_index(10, ValidatorIndex(validator_index), CoreIndex(3), false),
+				ChunkIndex(validator_index),
+			);
+		}
+	}
+
+	#[test]
+	fn mapping_with_no_validators_is_identity() {
+		assert_eq!(get_chunk_index(0, ValidatorIndex(0), CoreIndex(5), true), ChunkIndex(0));
+		assert_eq!(get_chunk_index(0, ValidatorIndex(7), CoreIndex(5), true), ChunkIndex(7));
+	}
+
+	#[test]
+	fn mapping_rotates_per_core() {
+		let n_validators = 10;
+
+		// `core_offset == core_index` while `core_index < n_validators`, so the rotation is
+		// a plain wrap-around shift by the core index.
+		for validator_index in 0..n_validators {
+			assert_eq!(
+				get_chunk_index(n_validators as usize, ValidatorIndex(validator_index), CoreIndex(0), true),
+				ChunkIndex(validator_index),
+			);
+			assert_eq!(
+				get_chunk_index(n_validators as usize, ValidatorIndex(validator_index), CoreIndex(3), true),
+				ChunkIndex((validator_index + 3) % n_validators),
+			);
+		}
+
+		// Different cores must not all map validator 0 onto chunk 0.
+		assert_ne!(
+			get_chunk_index(n_validators as usize, ValidatorIndex(0), CoreIndex(1), true),
+			get_chunk_index(n_validators as usize, ValidatorIndex(0), CoreIndex(2), true),
+		);
+	}
+
+	#[test]
+	fn systematic_chunk_count_rounds_up() {
+		assert_eq!(systematic_chunk_count(0), 0);
+		assert_eq!(systematic_chunk_count(1), 1);
+		assert_eq!(systematic_chunk_count(3), 1);
+		assert_eq!(systematic_chunk_count(4), 2);
+		assert_eq!(systematic_chunk_count(9), 3);
+		assert_eq!(systematic_chunk_count(10), 4);
+	}
+}
@@ -17,65 +17,40 @@
 
 //! Error handling related code and Error/Result definitions.
 
+use fatality::Fatality;
 use polkadot_node_network_protocol::request_response::request::RequestError;
 use polkadot_primitives::v1::SessionIndex;
-use thiserror::Error;
 
 use futures::channel::oneshot;
 
-use polkadot_node_subsystem_util::{Fault, Error as UtilError, runtime, unwrap_non_fatal};
+use polkadot_node_subsystem_util::{runtime, Error as UtilError};
 use polkadot_subsystem::{errors::RuntimeApiError, SubsystemError};
 
-use crate::LOG_TARGET;
+use crate::{metrics::Metrics, LOG_TARGET};
 
-#[derive(Debug, Error)]
-#[error(transparent)]
-pub struct Error(pub Fault<NonFatal, Fatal>);
-
-impl From<NonFatal> for Error {
-	fn from(e: NonFatal) -> Self {
-		Self(Fault::from_non_fatal(e))
-	}
-}
-
-impl From<Fatal> for Error {
-	fn from(f: Fatal) -> Self {
-		Self(Fault::from_fatal(f))
-	}
-}
-
-impl From<runtime::Error> for Error {
-	fn from(o: runtime::Error) -> Self {
-		Self(Fault::from_other(o))
-	}
-}
-
-/// Fatal errors of this subsystem.
-#[derive(Debug, Error)]
-pub enum Fatal {
+/// Errors of this subsystem.
+#[derive(Debug, Fatality)]
+#[fatality(splitable)]
+pub enum Error {
 	/// Spawning a running task failed.
+	#[fatal]
 	#[error("Spawning subsystem task failed")]
 	SpawnTask(#[source] SubsystemError),
 
 	/// Runtime API subsystem is down, which means we're shutting down.
+	#[fatal]
 	#[error("Runtime request canceled")]
-	RuntimeRequestCanceled(oneshot::Canceled),
+	RuntimeRequestCanceled(#[source] oneshot::Canceled),
 
 	/// Requester stream exhausted.
+	#[fatal]
 	#[error("Erasure chunk requester stream exhausted")]
 	RequesterExhausted,
 
+	#[fatal]
 	#[error("Receive channel closed")]
 	IncomingMessageChannel(#[source] SubsystemError),
 
-	/// Errors coming from runtime::Runtime.
-	#[error("Error while accessing runtime information")]
-	Runtime(#[from] #[source] runtime::Fatal),
-}
-
-/// Non fatal errors of this subsystem.
-#[derive(Debug, Error)]
-pub enum NonFatal {
 	/// av-store will drop the sender on any error that happens.
 	#[error("Response channel to obtain chunk failed")]
 	QueryChunkResponseChannel(#[source] oneshot::Canceled),
@@ -99,12 +74,12 @@ pub enum NonFatal {
 	/// Some request to utility functions failed.
 	/// This can be either `RuntimeRequestCanceled` or `RuntimeApiError`.
 	#[error("Utility request failed")]
-	UtilRequest(UtilError),
+	UtilRequest(#[source] UtilError),
 
 	/// Some request to the runtime failed.
 	/// For example if we prune a block we're requesting info about.
 	#[error("Runtime API error")]
-	RuntimeRequest(RuntimeApiError),
+	RuntimeRequest(#[source] RuntimeApiError),
 
 	/// Fetching PoV failed with `RequestError`.
 	#[error("FetchPoV request error")]
@@ -117,6 +92,20 @@ pub enum NonFatal {
 	#[error("Remote responded with `NoSuchPoV`")]
 	NoSuchPoV,
 
+	/// Systematic chunk recovery failed, we will fall back to regular recovery.
+	#[error("Systematic chunk recovery failed")]
+	SystematicRecoveryFailed,
+
+	/// The `ValidatorIndex -> ChunkIndex` mapping computed for a core mapped two validators
+	/// onto the same systematic chunk, or produced an index that does not fit a chunk slot.
+	#[error("Invalid chunk index mapping")]
+	InvalidChunkIndexMapping,
+
+	/// Marker variant we log/meter when systematic recovery gave up and we moved on to the
+	/// existing "collect any k chunks and decode" path.
+	#[error("Falling back to regular chunk recovery")]
+	FallbackToRegularRecovery,
+
 	/// No validator with the index could be found in current session.
 	#[error("Given validator index could not be found")]
 	InvalidValidatorIndex,
@@ -125,22 +114,58 @@ pub enum NonFatal {
 	#[error("There was no session with the given index")]
 	NoSuchSession(SessionIndex),
 
-	/// Errors coming from runtime::Runtime.
+	/// Errors coming from runtime::Runtime. Fatality is forwarded from the inner error, so this
+	/// subsystem does not need to be updated whenever `runtime::Error` grows a new variant.
+	#[fatal(forward)]
 	#[error("Error while accessing runtime information")]
-	Runtime(#[from] #[source] runtime::NonFatal),
+	Runtime(#[from] runtime::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl JfyiError {
+	/// A short, stable label identifying the failure class, suitable for use as a Prometheus
+	/// metric label. Kept exhaustive on purpose: a new variant must be labelled here before it
+	/// will compile.
+	pub fn as_metric_label(&self) -> &'static str {
+		match self {
+			Self::QueryChunkResponseChannel(_) => "query_chunk_channel",
+			Self::QueryAvailableDataResponseChannel(_) => "query_available_data_channel",
+			Self::NoSuchCachedSession => "no_such_cached_session",
+			Self::NotAValidator => "not_a_validator",
+			Self::SendResponse => "send_response",
+			Self::UtilRequest(_) => "util_request",
+			Self::RuntimeRequest(_) => "runtime_request",
+			Self::FetchPoV(_) => "fetch_pov",
+			Self::UnexpectedPoV => "unexpected_pov",
+			Self::NoSuchPoV => "no_such_pov",
+			Self::SystematicRecoveryFailed => "systematic_recovery_failed",
+			Self::InvalidChunkIndexMapping => "invalid_chunk_index_mapping",
+			Self::FallbackToRegularRecovery => "fallback_to_regular_recovery",
+			Self::InvalidValidatorIndex => "invalid_validator_index",
+			Self::NoSuchSession(_) => "no_such_session",
+			Self::Runtime(_) => "runtime_helper",
+		}
+	}
+}
+
 /// Utility for eating top level errors and log them.
 ///
 /// We basically always want to try and continue on error. This utility function is meant to
-/// consume top-level errors by simply logging them
-pub fn log_error(result: Result<()>, ctx: &'static str)
-	-> std::result::Result<(), Fatal>
-{
-	if let Some(error) = unwrap_non_fatal(result.map_err(|e| e.0))? {
-		tracing::warn!(target: LOG_TARGET, error = ?error, ctx);
+/// consume top-level errors by simply logging them and bumping the corresponding error metric.
+pub fn log_error(
+	result: Result<()>,
+	metrics: &Metrics,
+	ctx: &'static str,
+) -> std::result::Result<(), FatalError> {
+	if let Err(error) = result {
+		match error.split() {
+			Ok(jfyi) => {
+				metrics.on_error(jfyi.as_metric_label());
+				tracing::warn!(target: LOG_TARGET, error = ?jfyi, ctx);
+			},
+			Err(fatal) => return Err(fatal),
+		}
 	}
 	Ok(())
 }
@@ -149,8 +174,6 @@ pub fn log_error(result: Result<()>, ctx: &'static str)
 pub(crate) async fn recv_runtime<V>(
 	r: oneshot::Receiver<std::result::Result<V, RuntimeApiError>>,
 ) -> Result<V> {
-	let result = r.await
-		.map_err(Fatal::RuntimeRequestCanceled)?
-		.map_err(NonFatal::RuntimeRequest)?;
+	let result = r.await.map_err(Error::RuntimeRequestCanceled)?.map_err(Error::RuntimeRequest)?;
 	Ok(result)
 }
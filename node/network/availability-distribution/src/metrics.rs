@@ -0,0 +1,59 @@
+// Copyright 2023 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Prometheus metrics for this subsystem.
+
+use polkadot_node_subsystem_util::metrics::{self, prometheus};
+
+/// Availability distribution metrics.
+#[derive(Clone, Default)]
+pub struct Metrics(Option<MetricsInner>);
+
+#[derive(Clone)]
+struct MetricsInner {
+	/// Non-fatal errors, by failure class.
+	errors: prometheus::CounterVec<prometheus::U64>,
+}
+
+impl Metrics {
+	/// Record a non-fatal error, labelled by its metric label.
+	pub fn on_error(&self, label: &'static str) {
+		if let Some(metrics) = &self.0 {
+			metrics.errors.with_label_values(&[label]).inc();
+		}
+	}
+}
+
+impl metrics::Metrics for Metrics {
+	fn try_register(
+		registry: &prometheus::Registry,
+	) -> Result<Self, prometheus::PrometheusError> {
+		let metrics = MetricsInner {
+			errors: prometheus::register(
+				prometheus::CounterVec::new(
+					prometheus::Opts::new(
+						"polkadot_parachain_availability_distribution_errors_total",
+						"Number of non-fatal errors in availability-distribution, by failure class",
+					),
+					&["reason"],
+				)?,
+				registry,
+			)?,
+		};
+		Ok(Metrics(Some(metrics)))
+	}
+}
@@ -0,0 +1,362 @@
+// Copyright 2023 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Erasure chunk fetching with a systematic-recovery fast path.
+//!
+//! For large PoVs, running Reed-Solomon decoding over an arbitrary `k` chunks is expensive.
+//! If we instead fetch the first `ceil(n / 3)` *systematic* chunks, the available data can be
+//! reconstructed by plain concatenation. We therefore always try systematic recovery first and
+//! only fall back to the existing "collect any k chunks and decode" path if it doesn't pan out
+//! (not enough systematic chunks could be fetched). As a secondary backstop, chunks missing
+//! from their systematic holder are requested from the candidate's backers instead, one missing
+//! chunk per backer so a single backer can't be asked to cover for the whole validator set.
+
+use parity_scale_codec::Decode;
+use polkadot_erasure_coding::branch_hash;
+use polkadot_primitives::v1::{
+	node_features::FeatureIndex, AvailableData, CoreIndex, Hash, NodeFeatures, ValidatorIndex,
+};
+use sp_core::blake2_256;
+
+use crate::{
+	chunk_index_mapping::{get_chunk_index, systematic_chunk_count, ChunkIndex},
+	error::{log_error, Error, Result},
+	metrics::Metrics,
+	LOG_TARGET,
+};
+
+/// An erasure chunk together with the Merkle proof tying it to the candidate's erasure root.
+pub struct FetchedChunk {
+	/// The raw chunk bytes.
+	pub data: Vec<u8>,
+	/// Merkle proof of `data`'s inclusion in the erasure trie rooted at the candidate's
+	/// erasure root, at the chunk's index.
+	pub proof: Vec<Vec<u8>>,
+}
+
+/// Whatever is needed to go fetch a single erasure chunk from a specific validator or backer.
+///
+/// Kept as a trait so the systematic-then-fallback control flow below can be exercised without
+/// a live network: production code backs it with the actual chunk request/response protocol,
+/// tests can back it with an in-memory chunk set.
+#[async_trait::async_trait]
+pub trait ChunkFetcher {
+	/// Ask `from` for the chunk at `chunk_index`: its own chunk, or, for a backer, a chunk it
+	/// covers for. Returns `None` if the request failed or the peer did not have the chunk.
+	async fn fetch_chunk(&self, from: ValidatorIndex, chunk_index: ChunkIndex) -> Option<FetchedChunk>;
+
+	/// Run the existing "collect any k chunks and run Reed-Solomon decode" path. Unaffected by
+	/// systematic recovery; only reached once it has given up.
+	async fn recover_regular(&self) -> Result<AvailableData>;
+}
+
+/// Whether the `FeatureIndex::AvailabilityChunkMapping` node feature bit is enabled on-chain for the
+/// session the candidate was backed in. Until it is, `chunk_index == validator_index` and
+/// systematic recovery always asks the same first third of validators.
+pub fn chunk_mapping_enabled(node_features: &NodeFeatures) -> bool {
+	node_features.get(FeatureIndex::AvailabilityChunkMapping as usize).unwrap_or(false)
+}
+
+/// Check a fetched chunk against the candidate's erasure root before trusting its bytes.
+fn verify_chunk(erasure_root: &Hash, chunk_index: ChunkIndex, chunk: &FetchedChunk) -> bool {
+	match branch_hash(erasure_root, &chunk.proof, chunk_index.0 as usize) {
+		Ok(expected) => expected.as_bytes() == blake2_256(&chunk.data),
+		Err(_) => false,
+	}
+}
+
+/// Recover the available data for a candidate on `core_index`, preferring systematic recovery
+/// and falling back to the regular any-`k`-chunks decode path if it doesn't pan out.
+pub async fn recover_available_data<F: ChunkFetcher>(
+	fetcher: &F,
+	metrics: &Metrics,
+	erasure_root: Hash,
+	n_validators: usize,
+	core_index: CoreIndex,
+	node_features: &NodeFeatures,
+	backers: &[ValidatorIndex],
+) -> Result<AvailableData> {
+	let mapping_enabled = chunk_mapping_enabled(node_features);
+
+	match fetch_systematic_chunks(
+		fetcher,
+		erasure_root,
+		n_validators,
+		core_index,
+		mapping_enabled,
+		backers,
+	)
+	.await
+	.and_then(reconstruct_from_systematic_chunks)
+	{
+		Ok(available_data) => Ok(available_data),
+		Err(err) => {
+			log_error(Err(err), metrics, "fetching systematic chunks")?;
+			log_error(
+				Err(Error::FallbackToRegularRecovery),
+				metrics,
+				"falling back to regular chunk recovery",
+			)?;
+			fetcher.recover_regular().await
+		},
+	}
+}
+
+/// Attempt to fetch the first `systematic_chunk_count(n_validators)` systematic chunks,
+/// querying each validator's systematic holder first and falling back to the candidate's
+/// backers (one missing chunk per backer) for whatever didn't come back. Every chunk is
+/// verified against `erasure_root` before being accepted; a chunk that fails verification is
+/// treated the same as one that was never received.
+async fn fetch_systematic_chunks<F: ChunkFetcher>(
+	fetcher: &F,
+	erasure_root: Hash,
+	n_validators: usize,
+	core_index: CoreIndex,
+	chunk_mapping_enabled: bool,
+	backers: &[ValidatorIndex],
+) -> Result<Vec<Vec<u8>>> {
+	let needed = systematic_chunk_count(n_validators);
+	let mut chunks: Vec<Option<Vec<u8>>> = vec![None; needed];
+	let mut missing = Vec::new();
+
+	for validator in 0..n_validators as u32 {
+		let validator_index = ValidatorIndex(validator);
+		let chunk_index =
+			get_chunk_index(n_validators, validator_index, core_index, chunk_mapping_enabled);
+
+		let slot = match usize::try_from(chunk_index.0) {
+			// Not one of the systematic chunks we need; this validator holds a chunk outside
+			// of the systematic range.
+			Ok(slot) if slot >= needed => continue,
+			Ok(slot) => slot,
+			Err(_) => return Err(Error::InvalidChunkIndexMapping),
+		};
+
+		if chunks[slot].is_some() {
+			// Two validators were mapped onto the same systematic slot: the rotation is
+			// supposed to be a bijection on `0..n_validators`, so this means the feature-gated
+			// mapping computation itself is broken.
+			return Err(Error::InvalidChunkIndexMapping)
+		}
+
+		match fetcher.fetch_chunk(validator_index, chunk_index).await {
+			Some(chunk) if verify_chunk(&erasure_root, chunk_index, &chunk) =>
+				chunks[slot] = Some(chunk.data),
+			_ => missing.push((chunk_index, slot)),
+		}
+	}
+
+	// Backstop: ask the backers for the chunks whose systematic holder didn't have them (or
+	// served something that failed verification), one missing chunk per backer so a single
+	// backer can't be asked to cover for everyone.
+	for (backer, (chunk_index, slot)) in backers.iter().zip(missing) {
+		if let Some(chunk) = fetcher.fetch_chunk(*backer, chunk_index).await {
+			if verify_chunk(&erasure_root, chunk_index, &chunk) {
+				chunks[slot] = Some(chunk.data);
+			}
+		}
+	}
+
+	if chunks.iter().any(Option::is_none) {
+		tracing::debug!(
+			target: LOG_TARGET,
+			?core_index,
+			needed,
+			"Not enough systematic chunks available",
+		);
+		return Err(Error::SystematicRecoveryFailed)
+	}
+
+	Ok(chunks.into_iter().map(|c| c.expect("checked above; qed")).collect())
+}
+
+/// Reconstruct the available data by concatenating systematic chunks in chunk-index order and
+/// SCALE-decoding the result. Systematic chunks are literal slices of the SCALE-encoded,
+/// padded-to-a-multiple-of-`n_validators` `AvailableData`, so the concatenation of the first
+/// `ceil(n / 3)` of them is a prefix of that encoding containing the whole value; `Decode`
+/// simply stops once it has read enough, discarding the erasure-coding padding that follows.
+fn reconstruct_from_systematic_chunks(chunks: Vec<Vec<u8>>) -> Result<AvailableData> {
+	let encoded: Vec<u8> = chunks.into_iter().flatten().collect();
+	AvailableData::decode(&mut &encoded[..]).map_err(|_| Error::SystematicRecoveryFailed)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use parity_scale_codec::Encode;
+	use polkadot_erasure_coding::{branches, obtain_chunks_v1};
+	use polkadot_primitives::v1::{BlockData, PoV};
+
+	use super::*;
+
+	fn test_available_data() -> AvailableData {
+		AvailableData {
+			pov: std::sync::Arc::new(PoV { block_data: BlockData(vec![42; 256]) }),
+			validation_data: Default::default(),
+		}
+	}
+
+	/// Builds the full systematic+regular chunk set and erasure root for `n_validators`, so
+	/// tests can hand out real, independently-verifiable chunks.
+	fn build_chunks(n_validators: usize) -> (Hash, Vec<FetchedChunk>) {
+		let available_data = test_available_data();
+		let raw_chunks = obtain_chunks_v1(n_validators, &available_data).expect("encodes fine");
+		let branches = branches(raw_chunks);
+		let root = branches.root();
+		let chunks = branches
+			.map(|(proof, data)| FetchedChunk { data, proof })
+			.collect::<Vec<_>>();
+		(root, chunks)
+	}
+
+	struct MockFetcher {
+		erasure_root: Hash,
+		n_validators: usize,
+		chunks: HashMap<u32, FetchedChunk>,
+		/// Validators that never respond, regardless of which chunk is asked for.
+		offline: Vec<u32>,
+		regular_calls: std::sync::atomic::AtomicUsize,
+	}
+
+	impl MockFetcher {
+		fn new(n_validators: usize, chunks: Vec<FetchedChunk>, erasure_root: Hash) -> Self {
+			let chunks =
+				chunks.into_iter().enumerate().map(|(i, c)| (i as u32, c)).collect();
+			Self {
+				erasure_root,
+				n_validators,
+				chunks,
+				offline: Vec::new(),
+				regular_calls: Default::default(),
+			}
+		}
+
+		fn with_offline(mut self, offline: Vec<u32>) -> Self {
+			self.offline = offline;
+			self
+		}
+	}
+
+	#[async_trait::async_trait]
+	impl ChunkFetcher for MockFetcher {
+		async fn fetch_chunk(
+			&self,
+			from: ValidatorIndex,
+			chunk_index: ChunkIndex,
+		) -> Option<FetchedChunk> {
+			if self.offline.contains(&from.0) {
+				return None
+			}
+			// Any validator can be asked for any systematic chunk index (e.g. a backer asked
+			// for a chunk that wasn't originally theirs): hand back the chunk for that index
+			// directly, proof and all, as if it came from the network.
+			let FetchedChunk { data, proof } = self.chunks.get(&chunk_index.0)?;
+			Some(FetchedChunk { data: data.clone(), proof: proof.clone() })
+		}
+
+		async fn recover_regular(&self) -> Result<AvailableData> {
+			self.regular_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+			Ok(test_available_data())
+		}
+	}
+
+	const CORE: CoreIndex = CoreIndex(0);
+	const NO_BACKERS: &[ValidatorIndex] = &[];
+
+	#[tokio::test]
+	async fn recovers_when_every_systematic_holder_responds() {
+		let n_validators = 10;
+		let (root, chunks) = build_chunks(n_validators);
+		let fetcher = MockFetcher::new(n_validators, chunks, root);
+
+		let recovered =
+			fetch_systematic_chunks(&fetcher, root, n_validators, CORE, false, NO_BACKERS)
+				.await
+				.expect("all systematic holders are online");
+		assert_eq!(recovered.len(), systematic_chunk_count(n_validators));
+	}
+
+	#[tokio::test]
+	async fn falls_back_to_backers_for_missing_systematic_chunks() {
+		let n_validators = 10;
+		let (root, chunks) = build_chunks(n_validators);
+		// Validator 0 (systematic holder of chunk 0 with mapping disabled) goes offline;
+		// validator 7 stands in as a backer and is asked for chunk 0 instead.
+		let fetcher = MockFetcher::new(n_validators, chunks, root).with_offline(vec![0]);
+		let backers = [ValidatorIndex(7)];
+
+		let recovered =
+			fetch_systematic_chunks(&fetcher, root, n_validators, CORE, false, &backers)
+				.await
+				.expect("backer covers for the offline systematic holder");
+		assert_eq!(recovered.len(), systematic_chunk_count(n_validators));
+	}
+
+	#[tokio::test]
+	async fn gives_up_when_no_backer_covers_a_missing_chunk() {
+		let n_validators = 10;
+		let (root, chunks) = build_chunks(n_validators);
+		let fetcher = MockFetcher::new(n_validators, chunks, root).with_offline(vec![0]);
+
+		let err = fetch_systematic_chunks(&fetcher, root, n_validators, CORE, false, NO_BACKERS)
+			.await
+			.unwrap_err();
+		assert!(matches!(err, Error::SystematicRecoveryFailed));
+	}
+
+	#[tokio::test]
+	async fn rejects_a_chunk_that_fails_the_merkle_proof_check() {
+		let n_validators = 10;
+		let (root, mut chunks) = build_chunks(n_validators);
+		// Tamper with validator 0's chunk; it must be rejected even though it is "returned".
+		chunks[0].data[0] ^= 0xff;
+		let fetcher = MockFetcher::new(n_validators, chunks, root);
+
+		let err = fetch_systematic_chunks(&fetcher, root, n_validators, CORE, false, NO_BACKERS)
+			.await
+			.unwrap_err();
+		assert!(matches!(err, Error::SystematicRecoveryFailed));
+	}
+
+	#[tokio::test]
+	async fn falls_back_to_regular_recovery_when_systematic_recovery_fails() {
+		let n_validators = 10;
+		let (root, chunks) = build_chunks(n_validators);
+		// Knock out enough validators, with no backers to cover for them, that systematic
+		// recovery cannot possibly succeed.
+		let fetcher = MockFetcher::new(n_validators, chunks, root)
+			.with_offline((0..n_validators as u32).collect());
+		let metrics = Metrics::default();
+		let node_features = NodeFeatures::default();
+
+		let recovered = recover_available_data(
+			&fetcher,
+			&metrics,
+			root,
+			n_validators,
+			CORE,
+			&node_features,
+			NO_BACKERS,
+		)
+		.await
+		.expect("falls back to the regular path, which always succeeds in this mock");
+
+		assert_eq!(recovered, test_available_data());
+		assert_eq!(fetcher.regular_calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+	}
+}